@@ -16,21 +16,119 @@
 
 //! Storage vec abstraction on top of runtime storage, using hashed keys.
 
-use rstd::{prelude::*, borrow::Borrow};
+use rstd::{prelude::*, borrow::Borrow, marker::PhantomData};
 use codec::{Codec, KeyedVec};
 use runtime_io::{self, twox_128};
 use super::hashed;
 
+/// The hasher used to derive a `StorageVec`'s final trie keys.
+pub trait StorageHasher {
+	fn hash(data: &[u8]) -> Vec<u8>;
+}
+
+/// The default, non-cryptographic 128-bit hasher.
+pub struct Twox128;
+
+impl StorageHasher for Twox128 {
+	fn hash(data: &[u8]) -> Vec<u8> {
+		twox_128(data).to_vec()
+	}
+}
+
+/// A collision-resistant 128-bit hasher backed by the `blake3` crate.
+pub struct Blake3;
+
+impl StorageHasher for Blake3 {
+	fn hash(data: &[u8]) -> Vec<u8> {
+		blake3::hash(data).as_bytes()[..16].to_vec()
+	}
+}
+
+impl Blake3 {
+	/// Keyed 128-bit hash, using `key` as the domain-separation key.
+	pub fn keyed_hash(key: &[u8; 32], data: &[u8]) -> Vec<u8> {
+		blake3::keyed_hash(key, data).as_bytes()[..16].to_vec()
+	}
+
+	/// Derive a 32-byte subkey from a `context` string and some key `material`.
+	pub fn derive_key(context: &str, material: &[u8]) -> [u8; 32] {
+		blake3::derive_key(context, material)
+	}
+}
+
 /// A trait to conveniently store a vector of storable data.
 ///
-/// It uses twox_128 hasher. Final keys in trie are `twox_128(concatenation(PREFIX,count))`
+/// It uses twox_128 hasher by default. Final keys in trie are
+/// `twox_128(concatenation(PREFIX,count))`. Override `hash` with `Blake3::hash`
+/// for collision resistance.
 pub trait StorageVec {
 	type Item: Default + Sized + Codec;
 	const PREFIX: &'static [u8];
 
+	/// Optional context string domain-separating this collection's keys from any other
+	/// collection that happens to share the same `PREFIX`.
+	const CONTEXT: Option<&'static str> = None;
+
+	/// Hash used to derive the final trie key.
+	fn hash(data: &[u8]) -> Vec<u8> {
+		Twox128::hash(data)
+	}
+
+	/// The subkey derived from `CONTEXT`, if one is set.
+	fn context_subkey() -> Option<[u8; 32]> {
+		Self::CONTEXT.map(|context| Blake3::derive_key(context, Self::PREFIX))
+	}
+
+	/// Derive the final trie key for `data` from an already-computed `subkey` (see
+	/// `context_subkey`), falling back to `hash` when `subkey` is `None`.
+	fn hash_keyed(subkey: Option<[u8; 32]>, data: &[u8]) -> Vec<u8> {
+		match subkey {
+			Some(subkey) => Blake3::keyed_hash(&subkey, data),
+			None => Self::hash(data),
+		}
+	}
+
+	/// When `true`, per-index keys are squeezed from a single BLAKE3 XOF keystream rooted at
+	/// `PREFIX` (and, when set, `CONTEXT`) rather than hashed independently per index. Cheaper
+	/// for large sequential scans. The `len` key is unaffected, and this bypasses any
+	/// overridden `hash` — it's an alternative keying scheme, not an addition to it.
+	const XOF_KEYS: bool = false;
+
+	/// Start the XOF keystream used by `xof_index_key`, keyed by the context subkey when set.
+	fn xof_reader() -> blake3::OutputReader {
+		let mut hasher = match Self::context_subkey() {
+			Some(subkey) => blake3::Hasher::new_keyed(&subkey),
+			None => blake3::Hasher::new(),
+		};
+		hasher.update(Self::PREFIX);
+		hasher.finalize_xof()
+	}
+
+	/// The final trie key for `index` when `XOF_KEYS` is set.
+	fn xof_index_key(index: u32) -> Vec<u8> {
+		let mut reader = Self::xof_reader();
+		reader.set_position(index as u64 * 64);
+		let mut block = [0u8; 64];
+		reader.fill(&mut block);
+		block[..16].to_vec()
+	}
+
 	/// Get the current set of items.
 	fn items() -> Vec<Self::Item> {
-		(0..Self::count()).into_iter().map(Self::item).collect()
+		let count = Self::count();
+		if Self::XOF_KEYS {
+			let mut reader = Self::xof_reader();
+			(0..count).map(|index| {
+				reader.set_position(index as u64 * 64);
+				let mut block = [0u8; 64];
+				reader.fill(&mut block);
+				let key = block[..16].to_vec();
+				hashed::get_or_default(&move |_: &[u8]| key.clone(), &index.to_keyed_vec(Self::PREFIX))
+			}).collect()
+		} else {
+			let subkey = Self::context_subkey();
+			(0..count).map(|index| Self::item_keyed(subkey, index)).collect()
+		}
 	}
 
 	/// Set the current set of items.
@@ -41,9 +139,22 @@ pub trait StorageVec {
 	{
 		let mut count: u32 = 0;
 
-		for i in items.into_iter() {
-			hashed::put(&twox_128, &count.to_keyed_vec(Self::PREFIX), i.borrow());
-			count = count.checked_add(1).expect("exceeded runtime storage capacity");
+		if Self::XOF_KEYS {
+			let mut reader = Self::xof_reader();
+			for i in items.into_iter() {
+				reader.set_position(count as u64 * 64);
+				let mut block = [0u8; 64];
+				reader.fill(&mut block);
+				let key = block[..16].to_vec();
+				hashed::put(&move |_: &[u8]| key.clone(), &count.to_keyed_vec(Self::PREFIX), i.borrow());
+				count = count.checked_add(1).expect("exceeded runtime storage capacity");
+			}
+		} else {
+			let subkey = Self::context_subkey();
+			for i in items.into_iter() {
+				hashed::put(&move |d: &[u8]| Self::hash_keyed(subkey, d), &count.to_keyed_vec(Self::PREFIX), i.borrow());
+				count = count.checked_add(1).expect("exceeded runtime storage capacity");
+			}
 		}
 
 		Self::set_count(count);
@@ -52,33 +163,133 @@ pub trait StorageVec {
 	/// Push an item.
 	fn push(item: &Self::Item) {
 		let len = Self::count();
-		hashed::put(&twox_128, &len.to_keyed_vec(Self::PREFIX), item);
+		if Self::XOF_KEYS {
+			let key = Self::xof_index_key(len);
+			hashed::put(&move |_: &[u8]| key.clone(), &len.to_keyed_vec(Self::PREFIX), item);
+		} else {
+			let subkey = Self::context_subkey();
+			hashed::put(&move |d: &[u8]| Self::hash_keyed(subkey, d), &len.to_keyed_vec(Self::PREFIX), item);
+		}
 		Self::set_count(len + 1);
 	}
 
 	fn set_item(index: u32, item: &Self::Item) {
 		if index < Self::count() {
-			hashed::put(&twox_128, &index.to_keyed_vec(Self::PREFIX), item);
+			if Self::XOF_KEYS {
+				let key = Self::xof_index_key(index);
+				hashed::put(&move |_: &[u8]| key.clone(), &index.to_keyed_vec(Self::PREFIX), item);
+			} else {
+				let subkey = Self::context_subkey();
+				hashed::put(&move |d: &[u8]| Self::hash_keyed(subkey, d), &index.to_keyed_vec(Self::PREFIX), item);
+			}
 		}
 	}
 
 	fn clear_item(index: u32) {
 		if index < Self::count() {
-			hashed::kill(&twox_128, &index.to_keyed_vec(Self::PREFIX));
+			if Self::XOF_KEYS {
+				let key = Self::xof_index_key(index);
+				hashed::kill(&move |_: &[u8]| key.clone(), &index.to_keyed_vec(Self::PREFIX));
+			} else {
+				Self::clear_item_keyed(Self::context_subkey(), index);
+			}
 		}
 	}
 
+	/// Kill the backing key for `index` from an already-computed `subkey`, letting callers
+	/// that clear several indices in one operation derive the subkey once and reuse it.
+	/// Always keys through `hash_keyed`; `XOF_KEYS` is not consulted.
+	fn clear_item_keyed(subkey: Option<[u8; 32]>, index: u32) {
+		hashed::kill(&move |d: &[u8]| Self::hash_keyed(subkey, d), &index.to_keyed_vec(Self::PREFIX));
+	}
+
 	fn item(index: u32) -> Self::Item {
-		hashed::get_or_default(&twox_128, &index.to_keyed_vec(Self::PREFIX))
+		if Self::XOF_KEYS {
+			let key = Self::xof_index_key(index);
+			hashed::get_or_default(&move |_: &[u8]| key.clone(), &index.to_keyed_vec(Self::PREFIX))
+		} else {
+			Self::item_keyed(Self::context_subkey(), index)
+		}
+	}
+
+	/// Get the item at `index` from an already-computed `subkey` (see `context_subkey`).
+	/// Always keys through `hash_keyed`; `XOF_KEYS` is not consulted.
+	fn item_keyed(subkey: Option<[u8; 32]>, index: u32) -> Self::Item {
+		hashed::get_or_default(&move |d: &[u8]| Self::hash_keyed(subkey, d), &index.to_keyed_vec(Self::PREFIX))
 	}
 
 	fn set_count(count: u32) {
-		(count..Self::count()).for_each(Self::clear_item);
-		hashed::put(&twox_128, &b"len".to_keyed_vec(Self::PREFIX), &count);
+		if Self::XOF_KEYS {
+			(count..Self::count()).for_each(Self::clear_item);
+		} else {
+			let subkey = Self::context_subkey();
+			(count..Self::count()).for_each(|index| Self::clear_item_keyed(subkey, index));
+		}
+		let subkey = Self::context_subkey();
+		hashed::put(&move |d: &[u8]| Self::hash_keyed(subkey, d), &b"len".to_keyed_vec(Self::PREFIX), &count);
 	}
 
 	fn count() -> u32 {
-		hashed::get_or_default(&twox_128, &b"len".to_keyed_vec(Self::PREFIX))
+		hashed::get_or_default(&move |d: &[u8]| Self::hash_keyed(Self::context_subkey(), d), &b"len".to_keyed_vec(Self::PREFIX))
+	}
+
+	/// Remove the item at `index` by moving the last item into its slot and truncating,
+	/// returning the removed item. `O(1)`, but does not preserve ordering.
+	///
+	/// Does nothing and returns the default item if `index` is out of bounds.
+	fn swap_remove(index: u32) -> Self::Item {
+		let count = Self::count();
+		if index >= count {
+			return Default::default();
+		}
+
+		let item = Self::item(index);
+		let last = count - 1;
+		if index != last {
+			Self::set_item(index, &Self::item(last));
+		}
+		Self::set_count(last);
+		item
+	}
+
+	/// Yield every item in order, clearing the backing keys as the iterator advances.
+	fn drain() -> Drain<Self> where Self: Sized {
+		Drain { index: 0, count: Self::count(), _marker: PhantomData }
+	}
+}
+
+/// An iterator that empties a [`StorageVec`], killing each backing key as it advances. Any
+/// items not consumed, and `count`, are cleared when the iterator is dropped.
+pub struct Drain<T: StorageVec> {
+	index: u32,
+	count: u32,
+	_marker: PhantomData<T>,
+}
+
+impl<T: StorageVec> Iterator for Drain<T> {
+	type Item = T::Item;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		if self.index >= self.count {
+			return None;
+		}
+		let item = T::item(self.index);
+		T::clear_item(self.index);
+		self.index += 1;
+		Some(item)
+	}
+}
+
+impl<T: StorageVec> Drop for Drain<T> {
+	fn drop(&mut self) {
+		while self.index < self.count {
+			T::clear_item(self.index);
+			self.index += 1;
+		}
+		// Write the zeroed length directly rather than through `set_count`, which would
+		// additionally sweep `0..count()` clearing every key a second time.
+		let subkey = T::context_subkey();
+		hashed::put(&move |d: &[u8]| T::hash_keyed(subkey, d), &b"len".to_keyed_vec(T::PREFIX), &0u32);
 	}
 }
 
@@ -147,4 +358,159 @@ mod tests {
 			assert_eq!(x, y);
 		});
 	}
+
+	#[test]
+	fn blake3_matches_known_vectors() {
+		assert_eq!(
+			Blake3::hash(b""),
+			vec![
+				0xaf, 0x13, 0x49, 0xb9, 0xf5, 0xf9, 0xa1, 0xa6,
+				0xa0, 0x40, 0x4d, 0xea, 0x36, 0xdc, 0xc9, 0x49,
+			],
+		);
+		assert_eq!(
+			Blake3::hash(b"abc"),
+			vec![
+				0x64, 0x37, 0xb3, 0xac, 0x38, 0x46, 0x51, 0x33,
+				0xff, 0xb6, 0x3b, 0x75, 0x27, 0x3a, 0x8d, 0xb5,
+			],
+		);
+	}
+
+	#[test]
+	fn blake3_multi_chunk_hash_is_deterministic() {
+		let input = vec![0x42u8; 10_000];
+		assert_eq!(Blake3::hash(&input), Blake3::hash(&input));
+		assert_eq!(Blake3::hash(&input).len(), 16);
+	}
+
+	#[test]
+	fn blake3_keyed_hash_differs_by_key() {
+		let a = [1u8; 32];
+		let b = [2u8; 32];
+		assert_ne!(Blake3::keyed_hash(&a, b"data"), Blake3::keyed_hash(&b, b"data"));
+	}
+
+	#[test]
+	fn blake3_derive_key_differs_by_context() {
+		assert_ne!(Blake3::derive_key("a", b"material"), Blake3::derive_key("b", b"material"));
+	}
+
+	struct Plain;
+	impl StorageVec for Plain {
+		type Item = u32;
+		const PREFIX: &'static [u8] = b"plain_vec";
+	}
+
+	#[test]
+	fn swap_remove_moves_last_item_and_shrinks() {
+		let mut t = TestExternalities::default();
+		with_externalities(&mut t, || {
+			Plain::set_items(vec![10u32, 20, 30]);
+
+			assert_eq!(Plain::swap_remove(0), 10);
+			assert_eq!(Plain::items(), vec![30, 20]);
+			assert_eq!(Plain::count(), 2);
+		});
+	}
+
+	#[test]
+	fn swap_remove_out_of_bounds_is_a_no_op() {
+		let mut t = TestExternalities::default();
+		with_externalities(&mut t, || {
+			Plain::set_items(vec![1u32, 2]);
+			assert_eq!(Plain::swap_remove(5), 0);
+			assert_eq!(Plain::items(), vec![1, 2]);
+		});
+	}
+
+	#[test]
+	fn drain_clears_every_item_and_resets_count() {
+		let mut t = TestExternalities::default();
+		with_externalities(&mut t, || {
+			Plain::set_items(vec![1u32, 2, 3]);
+
+			assert_eq!(Plain::drain().collect::<Vec<_>>(), vec![1, 2, 3]);
+			assert_eq!(Plain::count(), 0);
+			assert_eq!(Plain::items(), Vec::<u32>::new());
+		});
+	}
+
+	#[test]
+	fn drain_dropped_early_still_clears_remaining_items_and_resets_count() {
+		let mut t = TestExternalities::default();
+		with_externalities(&mut t, || {
+			Plain::set_items(vec![1u32, 2, 3]);
+
+			{
+				let mut drain = Plain::drain();
+				assert_eq!(drain.next(), Some(1));
+			}
+
+			assert_eq!(Plain::count(), 0);
+			assert_eq!(Plain::items(), Vec::<u32>::new());
+		});
+	}
+
+	// Same PREFIX, different CONTEXT: the derived subkeys domain-separate the two collections
+	// even though they'd otherwise collide. The default `Twox128` hash is enough to prove this;
+	// overriding `hash` too would be dead code, since `hash_keyed` only falls back to it when
+	// no CONTEXT is set.
+	struct ContextA;
+	impl StorageVec for ContextA {
+		type Item = u32;
+		const PREFIX: &'static [u8] = b"shared_prefix";
+		const CONTEXT: Option<&'static str> = Some("module::a");
+	}
+
+	struct ContextB;
+	impl StorageVec for ContextB {
+		type Item = u32;
+		const PREFIX: &'static [u8] = b"shared_prefix";
+		const CONTEXT: Option<&'static str> = Some("module::b");
+	}
+
+	#[test]
+	fn context_domain_separates_colliding_prefixes() {
+		let mut t = TestExternalities::default();
+		with_externalities(&mut t, || {
+			ContextA::push(&1);
+			ContextB::push(&2);
+
+			assert_eq!(ContextA::items(), vec![1]);
+			assert_eq!(ContextB::items(), vec![2]);
+		});
+	}
+
+	struct XofContextA;
+	impl StorageVec for XofContextA {
+		type Item = u32;
+		const PREFIX: &'static [u8] = b"shared_xof_prefix";
+		const CONTEXT: Option<&'static str> = Some("module::xof_a");
+		const XOF_KEYS: bool = true;
+	}
+
+	struct XofContextB;
+	impl StorageVec for XofContextB {
+		type Item = u32;
+		const PREFIX: &'static [u8] = b"shared_xof_prefix";
+		const CONTEXT: Option<&'static str> = Some("module::xof_b");
+		const XOF_KEYS: bool = true;
+	}
+
+	#[test]
+	fn xof_keys_are_deterministic_and_context_separated() {
+		let mut t = TestExternalities::default();
+		with_externalities(&mut t, || {
+			XofContextA::set_items(vec![1u32, 2, 3]);
+			// Squeezing the same index twice yields the same key (deterministic).
+			assert_eq!(XofContextA::items(), vec![1, 2, 3]);
+
+			XofContextB::set_items(vec![4u32, 5, 6]);
+
+			// Same PREFIX and XOF_KEYS, different CONTEXT: no collision between collections.
+			assert_eq!(XofContextA::items(), vec![1, 2, 3]);
+			assert_eq!(XofContextB::items(), vec![4, 5, 6]);
+		});
+	}
 }